@@ -1,22 +1,22 @@
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::iter;
 use std::path::Path;
 
-const CHUNK_SIZE: usize = 1024;
+pub(crate) const DEFAULT_CHUNK_SIZE: usize = 1024;
 
-fn pad_vec(data: &[u8]) -> Vec<u8> {
+pub(crate) fn pad_vec(data: &[u8], chunk_size: usize) -> Vec<u8> {
     let mut result = data.to_vec();
-    while result.len() < CHUNK_SIZE {
+    while result.len() < chunk_size {
         result.push(0u8);
     }
     result
 }
 
-pub fn pad_leaf_layer(data: &mut Vec<Vec<u8>>) {
+pub fn pad_leaf_layer(data: &mut Vec<Vec<u8>>, leaf_size: usize) {
     let next_power_of_two = get_next_power_of_two(data.len());
     while data.len() < next_power_of_two {
-        let payload = iter::repeat(0u8).take(32).collect();
+        let payload = iter::repeat(0u8).take(leaf_size).collect();
         data.push(payload);
     }
 }
@@ -33,16 +33,18 @@ fn get_next_power_of_two(amount: usize) -> usize {
     num
 }
 
-pub fn split_file_to_chunks(filename: impl AsRef<Path>) -> Vec<Vec<u8>> {
+pub(crate) fn piece_count(file_size: u64, chunk_size: usize) -> usize {
+    ((file_size + chunk_size as u64 - 1) / chunk_size as u64) as usize
+}
+
+pub fn read_piece_bytes(filename: impl AsRef<Path>, offset: u64, len: usize, chunk_size: usize) -> Vec<u8> {
     let mut file = File::open(filename).expect("no such file");
-    let mut buffer = Vec::new();
+    file.seek(SeekFrom::Start(offset)).expect("seek failed");
 
-    file.read_to_end(&mut buffer);
+    let mut buffer = vec![0u8; len];
+    file.read_exact(&mut buffer).expect("read failed");
 
-    buffer
-        .chunks(CHUNK_SIZE)
-        .map(|chunk| pad_vec(chunk))
-        .collect()
+    pad_vec(&buffer, chunk_size)
 }
 
 #[cfg(test)]
@@ -52,9 +54,9 @@ mod tests {
     #[test]
     fn test_chunk_size() {
         let expect = 17;
-        let chunks = split_file_to_chunks("test_data/icons_rgb_circle.png");
+        let file_size = std::fs::metadata("test_data/icons_rgb_circle.png").unwrap().len();
 
-        assert_eq!(chunks.len(), expect);
+        assert_eq!(piece_count(file_size, DEFAULT_CHUNK_SIZE), expect);
     }
 
     #[test]
@@ -68,8 +70,16 @@ mod tests {
     #[test]
     fn test_pad_result() {
         let expect = 32;
-        let mut chunks = split_file_to_chunks("test_data/icons_rgb_circle.png");
-        pad_leaf_layer(&mut chunks);
+        let mut chunks: Vec<Vec<u8>> = (0..17).map(|_| vec![0u8; 32]).collect();
+        pad_leaf_layer(&mut chunks, 32);
         assert_eq!(chunks.len(), expect);
     }
+
+    #[test]
+    fn test_read_piece_bytes_pads_short_tail() {
+        let file_size = std::fs::metadata("test_data/small.txt").unwrap().len();
+        let piece = read_piece_bytes("test_data/small.txt", 0, file_size as usize, DEFAULT_CHUNK_SIZE);
+
+        assert_eq!(piece.len(), DEFAULT_CHUNK_SIZE);
+    }
 }
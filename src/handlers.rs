@@ -1,17 +1,36 @@
-use crate::MerkleTree;
+use crate::file_utils::DEFAULT_CHUNK_SIZE;
+use crate::merkle_tree::Algorithm;
+use crate::mmr::MerkleMountainRange;
+use crate::store::FsStore;
+use crate::AnyMerkleTree;
 use actix_web::body::BoxBody;
-use actix_web::error::ErrorBadRequest;
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
 use actix_web::http::header::ContentType;
 
 use actix_web::{web, Error, HttpRequest, HttpResponse, Responder, Result};
-use serde::Serialize;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub type Trees = Mutex<HashMap<String, AnyMerkleTree>>;
+pub type Store = Mutex<FsStore>;
+pub type Streams = Mutex<HashMap<String, MerkleMountainRange>>;
 
 #[derive(Serialize)]
-struct HashesResponse {
+struct HashInfo {
     hash: String,
     pieces: usize,
+    algorithm: &'static str,
+    chunk_size: usize,
+}
+
+#[derive(Serialize)]
+pub struct HashesResponse {
+    files: Vec<HashInfo>,
 }
 
 #[derive(Serialize)]
@@ -20,6 +39,29 @@ pub struct PiecesResponse {
     proof: Vec<String>,
 }
 
+#[derive(Serialize)]
+pub struct VerifyResponse {
+    valid: bool,
+}
+
+#[derive(Serialize)]
+pub struct UploadResponse {
+    hash: String,
+    pieces: usize,
+}
+
+#[derive(Deserialize)]
+pub struct UploadQuery {
+    filename: String,
+    algorithm: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct StreamRootResponse {
+    root: Option<String>,
+    leaves: usize,
+}
+
 impl Responder for HashesResponse {
     type Body = BoxBody;
 
@@ -46,36 +88,173 @@ impl Responder for PiecesResponse {
     }
 }
 
-pub async fn get_hashes(trees: web::Data<HashMap<String, MerkleTree>>) -> impl Responder {
-    let current_tree = trees.values().next().unwrap();
-    HashesResponse {
-        hash: hex::encode(&current_tree.nodes[1]),
-        pieces: current_tree.total_non_empty_pieces,
+impl Responder for VerifyResponse {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let body = serde_json::to_string(&self).unwrap();
+
+        // Create response and set content type
+        HttpResponse::Ok()
+            .content_type(ContentType::json())
+            .body(body)
+    }
+}
+
+impl Responder for UploadResponse {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let body = serde_json::to_string(&self).unwrap();
+
+        // Create response and set content type
+        HttpResponse::Ok()
+            .content_type(ContentType::json())
+            .body(body)
+    }
+}
+
+impl Responder for StreamRootResponse {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let body = serde_json::to_string(&self).unwrap();
+
+        // Create response and set content type
+        HttpResponse::Ok()
+            .content_type(ContentType::json())
+            .body(body)
     }
 }
 
+pub async fn get_hashes(trees: web::Data<Trees>) -> impl Responder {
+    let trees = trees.lock().unwrap();
+    let files = trees
+        .iter()
+        .map(|(hash, tree)| HashInfo {
+            hash: hash.clone(),
+            pieces: tree.total_non_empty_pieces(),
+            algorithm: tree.algorithm(),
+            chunk_size: tree.chunk_size(),
+        })
+        .collect();
+
+    HashesResponse { files }
+}
+
 pub async fn get_piece(
-    trees: web::Data<HashMap<String, MerkleTree>>,
+    trees: web::Data<Trees>,
     path: web::Path<(String, String)>,
 ) -> Result<PiecesResponse, Error> {
     let (hash_index, piece_index_str) = path.into_inner();
     let piece_index_num = piece_index_str.parse::<usize>().unwrap();
-    if !trees.contains_key(&*hash_index) {
-        return Err(ErrorBadRequest("No files available for hash requested"));
-    }
-
-    let current_tree = trees.get(&*hash_index).unwrap();
+    let trees = trees.lock().unwrap();
 
-    if !current_tree.piece_data.contains_key(&piece_index_num) {
-        return Err(ErrorBadRequest("Invalid piece requested"));
-    }
+    let current_tree = trees
+        .get(&*hash_index)
+        .ok_or_else(|| ErrorBadRequest("No files available for hash requested"))?;
 
-    let piece_data = current_tree.piece_data.get(&piece_index_num).unwrap();
+    let piece_bytes = current_tree
+        .read_piece(piece_index_num)
+        .ok_or_else(|| ErrorBadRequest("Invalid piece requested"))?;
 
     let response = PiecesResponse {
-        content: piece_data.to_owned(),
+        content: base64::encode(&piece_bytes),
         proof: current_tree.proof(piece_index_num).unwrap(),
     };
 
     return Ok(response);
 }
+
+pub async fn verify_piece(
+    trees: web::Data<Trees>,
+    path: web::Path<(String, String)>,
+) -> Result<VerifyResponse, Error> {
+    let (hash_index, piece_index_str) = path.into_inner();
+    let piece_index_num = piece_index_str.parse::<usize>().unwrap();
+    let trees = trees.lock().unwrap();
+
+    let current_tree = trees
+        .get(&*hash_index)
+        .ok_or_else(|| ErrorBadRequest("No files available for hash requested"))?;
+
+    let piece_bytes = current_tree
+        .read_piece(piece_index_num)
+        .ok_or_else(|| ErrorBadRequest("Invalid piece requested"))?;
+    let proof = current_tree.audit_path(piece_index_num).unwrap();
+
+    let valid = current_tree.verify(&hash_index, piece_index_num, &piece_bytes, &proof);
+
+    Ok(VerifyResponse { valid })
+}
+
+pub async fn upload(
+    trees: web::Data<Trees>,
+    store: web::Data<Store>,
+    directory: web::Data<PathBuf>,
+    query: web::Query<UploadQuery>,
+    mut body: web::Payload,
+) -> Result<UploadResponse, Error> {
+    let filename = Path::new(&query.filename)
+        .file_name()
+        .ok_or_else(|| ErrorBadRequest("Invalid filename"))?;
+    let target_path = directory.join(filename);
+    let algorithm = match &query.algorithm {
+        Some(name) => Algorithm::parse(name).ok_or_else(|| ErrorBadRequest("Unknown hash algorithm"))?,
+        None => Algorithm::Sha256,
+    };
+
+    let mut file = std::fs::File::create(&target_path)
+        .map_err(|err| ErrorInternalServerError(format!("failed to store upload: {}", err)))?;
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|err| ErrorBadRequest(format!("failed to read upload: {}", err)))?;
+        file.write_all(&chunk)
+            .map_err(|err| ErrorInternalServerError(format!("failed to store upload: {}", err)))?;
+    }
+
+    let mut store = store.lock().unwrap();
+    let tree = AnyMerkleTree::open(&target_path, DEFAULT_CHUNK_SIZE, algorithm, &mut *store);
+    let hash = tree.root_hex();
+    let pieces = tree.total_non_empty_pieces();
+
+    trees.lock().unwrap().insert(hash.clone(), tree);
+
+    Ok(UploadResponse { hash, pieces })
+}
+
+/// Appends one chunk to a named, in-memory Merkle Mountain Range,
+/// creating the stream on first use. Unlike `Trees`, a stream's root
+/// changes with every append, so it's keyed by a caller-chosen name
+/// rather than its own root hash.
+pub async fn append_stream(
+    streams: web::Data<Streams>,
+    name: web::Path<String>,
+    chunk: web::Bytes,
+) -> Result<StreamRootResponse, Error> {
+    let mut streams = streams.lock().unwrap();
+    let mmr = streams
+        .entry(name.into_inner())
+        .or_insert_with(MerkleMountainRange::new_default);
+    mmr.append(&chunk);
+
+    Ok(StreamRootResponse {
+        root: mmr.root(),
+        leaves: mmr.total_leaves,
+    })
+}
+
+pub async fn get_stream_root(
+    streams: web::Data<Streams>,
+    name: web::Path<String>,
+) -> Result<StreamRootResponse, Error> {
+    let streams = streams.lock().unwrap();
+    let mmr = streams
+        .get(&name.into_inner())
+        .ok_or_else(|| ErrorBadRequest("No such stream"))?;
+
+    Ok(StreamRootResponse {
+        root: mmr.root(),
+        leaves: mmr.total_leaves,
+    })
+}
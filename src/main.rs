@@ -1,36 +1,86 @@
-use crate::file_utils::{pad_leaf_layer, split_file_to_chunks};
-use crate::merkle_tree::MerkleTree;
+use crate::file_utils::{pad_leaf_layer, pad_vec, read_piece_bytes, DEFAULT_CHUNK_SIZE};
+use crate::handlers::{Store, Streams, Trees};
+use crate::merkle_tree::{Algorithm, AnyMerkleTree, MerkleTree};
+use crate::store::FsStore;
 
 use actix_web::{web, App, HttpServer};
 use std::collections::HashMap;
 use std::env;
 use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 mod file_utils;
 mod handlers;
 mod merkle_tree;
+mod mmr;
+mod store;
+
+fn load_directory(
+    directory: &Path,
+    node_store: &mut FsStore,
+    algorithm: Algorithm,
+) -> std::io::Result<HashMap<String, AnyMerkleTree>> {
+    let mut trees = HashMap::new();
+
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let tree = AnyMerkleTree::open(&path, DEFAULT_CHUNK_SIZE, algorithm, node_store);
+        trees.insert(tree.root_hex(), tree);
+    }
+
+    Ok(trees)
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let mut trees: HashMap<String, MerkleTree> = HashMap::new();
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 2 {
+    if args.len() < 2 || args.len() > 3 {
         Error::new(ErrorKind::Other, "Invalid number of arguments");
     }
 
-    let tree = MerkleTree::new(&args[1]);
+    let directory = PathBuf::from(&args[1]);
+    let algorithm = match args.get(2) {
+        Some(name) => Algorithm::parse(name).expect("unknown hash algorithm"),
+        None => Algorithm::Sha256,
+    };
+    let mut node_store = FsStore::open(".merkle-store")?;
+    let trees = load_directory(&directory, &mut node_store, algorithm)?;
 
-    trees.insert(hex::encode(&tree.nodes[1]), tree);
+    let trees: web::Data<Trees> = web::Data::new(Mutex::new(trees));
+    let node_store: web::Data<Store> = web::Data::new(Mutex::new(node_store));
+    let directory = web::Data::new(directory);
+    let streams: web::Data<Streams> = web::Data::new(Mutex::new(HashMap::new()));
 
     HttpServer::new(move || {
         App::new()
-            .app_data(actix_web::web::Data::new(trees.clone()))
+            .app_data(trees.clone())
+            .app_data(node_store.clone())
+            .app_data(directory.clone())
+            .app_data(streams.clone())
             .route("/hashes", web::get().to(handlers::get_hashes))
             .route(
                 "/piece/{hashId}/{pieceIndex}",
                 web::get().to(handlers::get_piece),
             )
+            .route(
+                "/verify/{hashId}/{pieceIndex}",
+                web::get().to(handlers::verify_piece),
+            )
+            .route("/upload", web::post().to(handlers::upload))
+            .route(
+                "/stream/{name}/append",
+                web::post().to(handlers::append_stream),
+            )
+            .route(
+                "/stream/{name}/root",
+                web::get().to(handlers::get_stream_root),
+            )
     })
     .bind(("127.0.0.1", 8080))?
     .run()
@@ -40,20 +90,23 @@ async fn main() -> std::io::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::handlers::{get_hashes, get_piece};
-    use actix_web::{
-        http::{header::ContentType},
-        test, web, App,
-    };
+    use crate::handlers::{append_stream, get_hashes, get_piece, get_stream_root, upload, verify_piece};
+    use actix_web::{http::header::ContentType, test, web, App};
+
+    fn trees_with(tree: MerkleTree) -> web::Data<Trees> {
+        let tree = AnyMerkleTree::Sha256(tree);
+        let mut trees: HashMap<String, AnyMerkleTree> = HashMap::new();
+        trees.insert(tree.root_hex(), tree);
+        web::Data::new(Mutex::new(trees))
+    }
 
     #[actix_web::test]
     async fn test_correct_hashes() {
-        let tree = MerkleTree::new("test_data/icons_rgb_circle.png");
-        let mut trees: HashMap<String, MerkleTree> = HashMap::new();
-        trees.insert(hex::encode(&tree.nodes[1]), tree);
+        let tree = MerkleTree::new_default("test_data/icons_rgb_circle.png");
+        let trees = trees_with(tree);
         let app = test::init_service(
             App::new()
-                .app_data(actix_web::web::Data::new(trees.clone()))
+                .app_data(trees.clone())
                 .route("/", web::get().to(get_hashes)),
         )
         .await;
@@ -66,12 +119,11 @@ mod tests {
 
     #[actix_web::test]
     async fn test_pieces_wrong_hash() {
-        let tree = MerkleTree::new("test_data/icons_rgb_circle.png");
-        let mut trees: HashMap<String, MerkleTree> = HashMap::new();
-        trees.insert(hex::encode(&tree.nodes[1]), tree);
+        let tree = MerkleTree::new_default("test_data/icons_rgb_circle.png");
+        let trees = trees_with(tree);
         let app = test::init_service(
             App::new()
-                .app_data(actix_web::web::Data::new(trees.clone()))
+                .app_data(trees.clone())
                 .route("/piece/{hashId}/{pieceIndex}", web::get().to(get_piece)),
         )
         .await;
@@ -84,12 +136,11 @@ mod tests {
 
     #[actix_web::test]
     async fn test_pieces_correct() {
-        let tree = MerkleTree::new("test_data/icons_rgb_circle.png");
-        let mut trees: HashMap<String, MerkleTree> = HashMap::new();
-        trees.insert(hex::encode(&tree.nodes[1]), tree);
+        let tree = MerkleTree::new_default("test_data/icons_rgb_circle.png");
+        let trees = trees_with(tree);
         let app = test::init_service(
             App::new()
-                .app_data(actix_web::web::Data::new(trees.clone()))
+                .app_data(trees.clone())
                 .route("/piece/{hashId}/{pieceIndex}", web::get().to(get_piece)),
         )
         .await;
@@ -102,14 +153,52 @@ mod tests {
         assert!(resp.status().is_success());
     }
 
+    #[actix_web::test]
+    async fn test_verify_piece_correct() {
+        let tree = MerkleTree::new_default("test_data/icons_rgb_circle.png");
+        let trees = trees_with(tree);
+        let app = test::init_service(
+            App::new()
+                .app_data(trees.clone())
+                .route("/verify/{hashId}/{pieceIndex}", web::get().to(verify_piece)),
+        )
+        .await;
+        let req = test::TestRequest::with_uri(
+            "/verify/9b39e1edb4858f7a3424d5a3d0c4579332640e58e101c29f99314a12329fc60b/4",
+        )
+        .insert_header(ContentType::plaintext())
+        .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["valid"], true);
+    }
+
+    #[actix_web::test]
+    async fn test_verify_piece_unknown_hash() {
+        let tree = MerkleTree::new_default("test_data/icons_rgb_circle.png");
+        let trees = trees_with(tree);
+        let app = test::init_service(
+            App::new()
+                .app_data(trees.clone())
+                .route("/verify/{hashId}/{pieceIndex}", web::get().to(verify_piece)),
+        )
+        .await;
+        let req = test::TestRequest::with_uri("/verify/asdf/4")
+            .insert_header(ContentType::plaintext())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(!resp.status().is_success());
+    }
+
     #[actix_web::test]
     async fn test_pieces_wrong_piece_number() {
-        let tree = MerkleTree::new("test_data/icons_rgb_circle.png");
-        let mut trees: HashMap<String, MerkleTree> = HashMap::new();
-        trees.insert(hex::encode(&tree.nodes[1]), tree);
+        let tree = MerkleTree::new_default("test_data/icons_rgb_circle.png");
+        let trees = trees_with(tree);
         let app = test::init_service(
             App::new()
-                .app_data(actix_web::web::Data::new(trees.clone()))
+                .app_data(trees.clone())
                 .route("/piece/{hashId}/{pieceIndex}", web::get().to(get_piece)),
         )
         .await;
@@ -121,4 +210,85 @@ mod tests {
         let resp = test::call_service(&app, req).await;
         assert!(!resp.status().is_success());
     }
+
+    #[actix_web::test]
+    async fn test_upload_streams_body_to_disk_and_returns_hash() {
+        let dir = std::env::temp_dir().join("merkle_file_server_upload_test");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let trees: web::Data<Trees> = web::Data::new(Mutex::new(HashMap::new()));
+        let store: web::Data<Store> =
+            web::Data::new(Mutex::new(FsStore::open(dir.join("store")).unwrap()));
+        let directory = web::Data::new(dir.clone());
+
+        let app = test::init_service(
+            App::new()
+                .app_data(trees.clone())
+                .app_data(store.clone())
+                .app_data(directory.clone())
+                .route("/upload", web::post().to(upload)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/upload?filename=uploaded.bin")
+            .set_payload(vec![b'X'; 64])
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert!(dir.join("uploaded.bin").exists());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[actix_web::test]
+    async fn test_stream_append_and_root_grow_together() {
+        let streams: web::Data<Streams> = web::Data::new(Mutex::new(HashMap::new()));
+        let app = test::init_service(
+            App::new()
+                .app_data(streams.clone())
+                .route("/stream/{name}/append", web::post().to(append_stream))
+                .route("/stream/{name}/root", web::get().to(get_stream_root)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/stream/video/append")
+            .set_payload(b"first chunk".to_vec())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let after_first: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(after_first["leaves"], 1);
+
+        let req = test::TestRequest::post()
+            .uri("/stream/video/append")
+            .set_payload(b"second chunk".to_vec())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let after_second: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(after_second["leaves"], 2);
+        assert_ne!(after_first["root"], after_second["root"]);
+
+        let req = test::TestRequest::with_uri("/stream/video/root").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let fetched: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(fetched, after_second);
+    }
+
+    #[actix_web::test]
+    async fn test_stream_root_unknown_name() {
+        let streams: web::Data<Streams> = web::Data::new(Mutex::new(HashMap::new()));
+        let app = test::init_service(
+            App::new()
+                .app_data(streams.clone())
+                .route("/stream/{name}/root", web::get().to(get_stream_root)),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/stream/missing/root").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(!resp.status().is_success());
+    }
 }
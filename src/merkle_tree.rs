@@ -1,35 +1,99 @@
-use crate::{pad_leaf_layer, split_file_to_chunks};
+use crate::file_utils::{piece_count, DEFAULT_CHUNK_SIZE};
+use crate::store::NodeStore;
+use crate::{pad_leaf_layer, pad_vec, read_piece_bytes};
+use blake2::Blake2s256;
 use hex;
 use sha2::{Digest, Sha256};
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::iter;
-use std::path::Path;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
 
-type Node = Vec<u8>;
+pub(crate) type Node = Vec<u8>;
 
-#[derive(Debug, Clone)]
-pub struct MerkleTree {
+pub trait HashAlgorithm: Digest {
+    const NAME: &'static str;
+}
+
+impl HashAlgorithm for Sha256 {
+    const NAME: &'static str = "sha256";
+}
+
+impl HashAlgorithm for Blake2s256 {
+    const NAME: &'static str = "blake2s256";
+}
+
+/// A digest a caller can pick at runtime (CLI flag, upload query param),
+/// resolved to a concrete `HashAlgorithm` type param wherever a tree is
+/// actually built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Blake2s256,
+}
+
+impl Algorithm {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sha256" => Some(Algorithm::Sha256),
+            "blake2s256" => Some(Algorithm::Blake2s256),
+            _ => None,
+        }
+    }
+}
+
+pub struct MerkleTree<H: HashAlgorithm = Sha256> {
     pub(crate) nodes: Vec<Node>,
     pub(crate) total_non_empty_pieces: usize,
     pub(crate) total_nodes: usize,
-    pub(crate) piece_data: HashMap<usize, String>,
+    pub(crate) piece_locations: HashMap<usize, (u64, u32)>,
+    pub(crate) source_path: PathBuf,
+    pub(crate) chunk_size: usize,
+    pub(crate) algorithm: &'static str,
+    _digest: PhantomData<H>,
 }
 
-fn hash_leaf(data: &Vec<u8>) -> Node {
-    let mut hasher = Sha256::new();
-    let zero_value: Vec<u8> = iter::repeat(0u8).take(32).collect();
+impl<H: HashAlgorithm> Clone for MerkleTree<H> {
+    fn clone(&self) -> Self {
+        MerkleTree {
+            nodes: self.nodes.clone(),
+            total_non_empty_pieces: self.total_non_empty_pieces,
+            total_nodes: self.total_nodes,
+            piece_locations: self.piece_locations.clone(),
+            source_path: self.source_path.clone(),
+            chunk_size: self.chunk_size,
+            algorithm: self.algorithm,
+            _digest: PhantomData,
+        }
+    }
+}
 
-    if *data != zero_value {
-        hasher.update(data);
+impl<H: HashAlgorithm> std::fmt::Debug for MerkleTree<H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MerkleTree")
+            .field("nodes", &self.nodes)
+            .field("total_non_empty_pieces", &self.total_non_empty_pieces)
+            .field("total_nodes", &self.total_nodes)
+            .field("piece_locations", &self.piece_locations)
+            .field("source_path", &self.source_path)
+            .field("chunk_size", &self.chunk_size)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+pub(crate) fn hash_leaf<H: HashAlgorithm>(data: &[u8]) -> Node {
+    let zero_value = vec![0u8; <H as Digest>::output_size()];
 
-        let mut result = hasher.finalize();
-        Node::from(result.as_mut_slice())
+    if data != zero_value.as_slice() {
+        let mut hasher = H::new();
+        hasher.update(data);
+        hasher.finalize().to_vec()
     } else {
-        data.clone()
+        zero_value
     }
 }
 
-fn populate_tree(data: &mut Vec<Vec<u8>>, pieces_length: &usize) {
+pub(crate) fn populate_tree<H: HashAlgorithm>(data: &mut Vec<Vec<u8>>, pieces_length: &usize) {
     let mut visited = HashSet::new();
     let mut queue = VecDeque::new();
 
@@ -47,11 +111,10 @@ fn populate_tree(data: &mut Vec<Vec<u8>>, pieces_length: &usize) {
         if 2 * index < data.len() && 2 * index + 1 < data.len() {
             let mut left = data[2 * index].clone();
             let mut right = data[2 * index + 1].clone();
-            let mut hasher = Sha256::new();
+            let mut hasher = H::new();
             left.append(&mut right);
             hasher.update(left);
-            let mut result = hasher.finalize();
-            data[index] = Node::from(result.as_mut_slice());
+            data[index] = hasher.finalize().to_vec();
         }
         if index / 2 > 0 {
             queue.push_back(index / 2);
@@ -59,6 +122,18 @@ fn populate_tree(data: &mut Vec<Vec<u8>>, pieces_length: &usize) {
     }
 }
 
+fn build_piece_locations(total_non_empty_pieces: usize, chunk_size: usize, file_size: u64) -> HashMap<usize, (u64, u32)> {
+    let mut locations = HashMap::new();
+
+    for index in 0..total_non_empty_pieces {
+        let offset = (index * chunk_size) as u64;
+        let len = std::cmp::min(chunk_size as u64, file_size - offset) as u32;
+        locations.insert(index, (offset, len));
+    }
+
+    locations
+}
+
 fn get_uncle(child_node: usize) -> Option<usize> {
     let parent = child_node / 2;
 
@@ -77,33 +152,62 @@ fn get_sibling(node: usize) -> usize {
     return if node % 2 == 0 { node + 1 } else { node - 1 };
 }
 
-impl MerkleTree {
-    pub fn new(file_name: impl AsRef<Path>) -> Self {
-        let mut pieces = &mut split_file_to_chunks(file_name);
-        let total_non_empty_pieces = pieces.len();
-        pad_leaf_layer(&mut pieces);
-        let total_nodes = 2 * pieces.len() - 1;
+pub(crate) fn heap_audit_path(nodes: &[Node], leaf_number: usize) -> Vec<(String, bool)> {
+    let mut steps = Vec::new();
+    let mut index = nodes.len() / 2 + leaf_number;
+
+    while index > 1 {
+        let sibling_is_left = index % 2 != 0;
+        let sibling = get_sibling(index);
+        steps.push((hex::encode(&nodes[sibling]), sibling_is_left));
+        index /= 2;
+    }
+
+    steps
+}
+
+impl<H: HashAlgorithm> MerkleTree<H> {
+    pub fn new(file_name: impl AsRef<Path>, chunk_size: usize) -> Self {
+        let path = file_name.as_ref().to_path_buf();
+        let file_size = std::fs::metadata(&path).expect("no such file").len();
+        let total_non_empty_pieces = piece_count(file_size, chunk_size);
+        let piece_locations = build_piece_locations(total_non_empty_pieces, chunk_size, file_size);
+
+        let mut leaves: Vec<Node> = (0..total_non_empty_pieces)
+            .map(|index| {
+                let (offset, len) = piece_locations[&index];
+                hash_leaf::<H>(&read_piece_bytes(&path, offset, len as usize, chunk_size))
+            })
+            .collect();
+
+        pad_leaf_layer(&mut leaves, <H as Digest>::output_size());
+        let leaf_layer_length = leaves.len();
+        let total_nodes = 2 * leaf_layer_length - 1;
         let mut result_data = vec![Node::new(); total_nodes + 1];
-        let mut base64_map = HashMap::new();
-        let leaf_layer_length = pieces.len();
-        for i in 0..total_non_empty_pieces {
-            base64_map.insert(i, base64::encode(&pieces[i]));
-        }
-        pieces
+        leaves
             .into_iter()
             .enumerate()
-            .for_each(|(i, piece)| result_data[i + leaf_layer_length] = hash_leaf(piece));
+            .for_each(|(i, leaf)| result_data[i + leaf_layer_length] = leaf);
 
-        populate_tree(&mut result_data, &pieces.len());
+        populate_tree::<H>(&mut result_data, &leaf_layer_length);
 
         MerkleTree {
             nodes: result_data,
             total_non_empty_pieces,
             total_nodes,
-            piece_data: base64_map,
+            piece_locations,
+            source_path: path,
+            chunk_size,
+            algorithm: H::NAME,
+            _digest: PhantomData,
         }
     }
 
+    pub fn read_piece(&self, piece_number: usize) -> Option<Vec<u8>> {
+        let &(offset, len) = self.piece_locations.get(&piece_number)?;
+        Some(read_piece_bytes(&self.source_path, offset, len as usize, self.chunk_size))
+    }
+
     pub fn uncle_traversal(&self, piece_number: usize) -> Option<Vec<String>> {
         if piece_number > self.total_non_empty_pieces - 1 {
             return None;
@@ -132,12 +236,313 @@ impl MerkleTree {
 
         Some(proof)
     }
+
+    pub fn audit_path(&self, piece_number: usize) -> Option<Vec<(String, bool)>> {
+        if piece_number > self.total_non_empty_pieces - 1 {
+            return None;
+        }
+        Some(heap_audit_path(&self.nodes, piece_number))
+    }
+
+    pub fn open(file_name: impl AsRef<Path>, chunk_size: usize, store: &mut dyn NodeStore) -> Self {
+        let path = file_name.as_ref();
+        let metadata = std::fs::metadata(path).expect("no such file");
+        let modified_nanos = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+        let key = path_key(path, chunk_size, H::NAME, metadata.len(), modified_nanos);
+
+        if let Some(root) = store.get(&key) {
+            if let Some(tree) = Self::load(path, &root, store) {
+                return tree;
+            }
+        }
+
+        let tree = Self::new(path, chunk_size);
+        tree.persist(store);
+        store.put(&key, tree.nodes[1].clone());
+
+        tree
+    }
+
+    fn persist(&self, store: &mut dyn NodeStore) {
+        let leaf_layer_length = (self.total_nodes + 1) / 2;
+
+        for index in 1..=self.total_nodes {
+            let entry = if index >= leaf_layer_length {
+                encode_node_entry(None)
+            } else {
+                encode_node_entry(Some((&self.nodes[2 * index], &self.nodes[2 * index + 1])))
+            };
+            store.put(&node_key(&self.nodes[index]), entry);
+        }
+
+        store.put(
+            &tree_key(&self.nodes[1]),
+            encode_tree_meta(self.total_non_empty_pieces, self.total_nodes, self.chunk_size),
+        );
+    }
+
+    fn load(path: &Path, root: &[u8], store: &mut dyn NodeStore) -> Option<Self> {
+        let (total_non_empty_pieces, total_nodes, chunk_size) =
+            decode_tree_meta(&store.get(&tree_key(root))?)?;
+        let leaf_layer_length = (total_nodes + 1) / 2;
+
+        let mut nodes = vec![Node::new(); total_nodes + 1];
+        nodes[1] = root.to_vec();
+        let mut queue = VecDeque::new();
+        queue.push_back(1usize);
+
+        while let Some(index) = queue.pop_front() {
+            if index >= leaf_layer_length {
+                continue;
+            }
+            let (left, right) = decode_node_entry(&store.get(&node_key(&nodes[index]))?)??;
+            nodes[2 * index] = left;
+            nodes[2 * index + 1] = right;
+            queue.push_back(2 * index);
+            queue.push_back(2 * index + 1);
+        }
+
+        let file_size = std::fs::metadata(path).ok()?.len();
+        let piece_locations = build_piece_locations(total_non_empty_pieces, chunk_size, file_size);
+
+        Some(MerkleTree {
+            nodes,
+            total_non_empty_pieces,
+            total_nodes,
+            piece_locations,
+            source_path: path.to_path_buf(),
+            chunk_size,
+            algorithm: H::NAME,
+            _digest: PhantomData,
+        })
+    }
+}
+
+impl MerkleTree<Sha256> {
+    pub fn new_default(file_name: impl AsRef<Path>) -> Self {
+        Self::new(file_name, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn open_default(file_name: impl AsRef<Path>, store: &mut dyn NodeStore) -> Self {
+        Self::open(file_name, DEFAULT_CHUNK_SIZE, store)
+    }
+}
+
+/// A `MerkleTree` whose digest was picked at runtime via `Algorithm`. The
+/// server only ever needs to build, persist and query trees, never to be
+/// generic over `H` itself, so a two-variant enum is simpler here than
+/// threading `H` through `Trees`/`Store` or reaching for a trait object.
+pub enum AnyMerkleTree {
+    Sha256(MerkleTree<Sha256>),
+    Blake2s256(MerkleTree<Blake2s256>),
+}
+
+impl AnyMerkleTree {
+    pub fn new(file_name: impl AsRef<Path>, chunk_size: usize, algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Sha256 => AnyMerkleTree::Sha256(MerkleTree::new(file_name, chunk_size)),
+            Algorithm::Blake2s256 => AnyMerkleTree::Blake2s256(MerkleTree::new(file_name, chunk_size)),
+        }
+    }
+
+    pub fn open(
+        file_name: impl AsRef<Path>,
+        chunk_size: usize,
+        algorithm: Algorithm,
+        store: &mut dyn NodeStore,
+    ) -> Self {
+        match algorithm {
+            Algorithm::Sha256 => AnyMerkleTree::Sha256(MerkleTree::open(file_name, chunk_size, store)),
+            Algorithm::Blake2s256 => AnyMerkleTree::Blake2s256(MerkleTree::open(file_name, chunk_size, store)),
+        }
+    }
+
+    pub fn root_hex(&self) -> String {
+        match self {
+            AnyMerkleTree::Sha256(tree) => hex::encode(&tree.nodes[1]),
+            AnyMerkleTree::Blake2s256(tree) => hex::encode(&tree.nodes[1]),
+        }
+    }
+
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            AnyMerkleTree::Sha256(tree) => tree.algorithm,
+            AnyMerkleTree::Blake2s256(tree) => tree.algorithm,
+        }
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        match self {
+            AnyMerkleTree::Sha256(tree) => tree.chunk_size,
+            AnyMerkleTree::Blake2s256(tree) => tree.chunk_size,
+        }
+    }
+
+    pub fn total_non_empty_pieces(&self) -> usize {
+        match self {
+            AnyMerkleTree::Sha256(tree) => tree.total_non_empty_pieces,
+            AnyMerkleTree::Blake2s256(tree) => tree.total_non_empty_pieces,
+        }
+    }
+
+    pub fn read_piece(&self, piece_number: usize) -> Option<Vec<u8>> {
+        match self {
+            AnyMerkleTree::Sha256(tree) => tree.read_piece(piece_number),
+            AnyMerkleTree::Blake2s256(tree) => tree.read_piece(piece_number),
+        }
+    }
+
+    pub fn proof(&self, piece_number: usize) -> Option<Vec<String>> {
+        match self {
+            AnyMerkleTree::Sha256(tree) => tree.proof(piece_number),
+            AnyMerkleTree::Blake2s256(tree) => tree.proof(piece_number),
+        }
+    }
+
+    pub fn audit_path(&self, piece_number: usize) -> Option<Vec<(String, bool)>> {
+        match self {
+            AnyMerkleTree::Sha256(tree) => tree.audit_path(piece_number),
+            AnyMerkleTree::Blake2s256(tree) => tree.audit_path(piece_number),
+        }
+    }
+
+    pub fn verify(&self, root: &str, piece_index: usize, piece_bytes: &[u8], proof: &[(String, bool)]) -> bool {
+        match self {
+            AnyMerkleTree::Sha256(tree) => {
+                verify::<Sha256>(root, piece_index, piece_bytes, tree.chunk_size, proof)
+            }
+            AnyMerkleTree::Blake2s256(tree) => {
+                verify::<Blake2s256>(root, piece_index, piece_bytes, tree.chunk_size, proof)
+            }
+        }
+    }
+}
+
+const TREE_KEY_PREFIX: &[u8] = b"tree:";
+const PATH_KEY_PREFIX: &[u8] = b"path:";
+const NODE_KEY_PREFIX: &[u8] = b"node:";
+
+fn tree_key(root: &[u8]) -> Vec<u8> {
+    [TREE_KEY_PREFIX, root].concat()
+}
+
+/// Binds the cache entry to the file's path *and* the content/algorithm it
+/// was last built from, so a changed file or a changed digest/chunk_size
+/// forces a rebuild instead of silently resurrecting a stale tree.
+fn path_key(path: &Path, chunk_size: usize, algorithm: &str, file_size: u64, modified_nanos: u128) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(path.to_string_lossy().as_bytes());
+    hasher.update(chunk_size.to_le_bytes());
+    hasher.update(algorithm.as_bytes());
+    hasher.update(file_size.to_le_bytes());
+    hasher.update(modified_nanos.to_le_bytes());
+    let digest = hasher.finalize();
+    [PATH_KEY_PREFIX, digest.as_slice()].concat()
+}
+
+/// Nodes are keyed by their own hash, so identical chunks or identical
+/// subtrees - whether repeated within one file or shared across several -
+/// are only ever stored once.
+fn node_key(hash: &[u8]) -> Vec<u8> {
+    [NODE_KEY_PREFIX, hash].concat()
+}
+
+/// `None` for a leaf, `Some((left, right))` for an internal node's children.
+fn encode_node_entry(children: Option<(&[u8], &[u8])>) -> Vec<u8> {
+    match children {
+        None => vec![0u8],
+        Some((left, right)) => {
+            let mut buf = vec![1u8];
+            buf.extend_from_slice(&(left.len() as u32).to_le_bytes());
+            buf.extend_from_slice(left);
+            buf.extend_from_slice(right);
+            buf
+        }
+    }
+}
+
+fn decode_node_entry(bytes: &[u8]) -> Option<Option<(Node, Node)>> {
+    match *bytes.first()? {
+        0 => Some(None),
+        1 => {
+            let mut pos = 1;
+            let left_len = read_u32(bytes, &mut pos)? as usize;
+            let left = bytes.get(pos..pos + left_len)?.to_vec();
+            pos += left_len;
+            let right = bytes.get(pos..)?.to_vec();
+            Some(Some((left, right)))
+        }
+        _ => None,
+    }
+}
+
+fn encode_tree_meta(total_non_empty_pieces: usize, total_nodes: usize, chunk_size: usize) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(total_non_empty_pieces as u64).to_le_bytes());
+    buf.extend_from_slice(&(total_nodes as u64).to_le_bytes());
+    buf.extend_from_slice(&(chunk_size as u64).to_le_bytes());
+    buf
+}
+
+fn decode_tree_meta(bytes: &[u8]) -> Option<(usize, usize, usize)> {
+    let mut pos = 0;
+    let total_non_empty_pieces = read_u64(bytes, &mut pos)? as usize;
+    let total_nodes = read_u64(bytes, &mut pos)? as usize;
+    let chunk_size = read_u64(bytes, &mut pos)? as usize;
+
+    Some((total_non_empty_pieces, total_nodes, chunk_size))
+}
+
+fn read_u64(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let end = *pos + 8;
+    let value = u64::from_le_bytes(bytes.get(*pos..end)?.try_into().ok()?);
+    *pos = end;
+    Some(value)
+}
+
+fn read_u32(bytes: &[u8], pos: &mut usize) -> Option<u32> {
+    let end = *pos + 4;
+    let value = u32::from_le_bytes(bytes.get(*pos..end)?.try_into().ok()?);
+    *pos = end;
+    Some(value)
+}
+
+pub fn verify<H: HashAlgorithm>(
+    root: &str,
+    _piece_index: usize,
+    piece_bytes: &[u8],
+    chunk_size: usize,
+    proof: &[(String, bool)],
+) -> bool {
+    let leaf = hash_leaf::<H>(&pad_vec(piece_bytes, chunk_size));
+    let folded = proof.iter().try_fold(leaf, |acc, (sibling_hex, sibling_is_left)| {
+        let sibling = hex::decode(sibling_hex).ok()?;
+        let mut hasher = H::new();
+        if *sibling_is_left {
+            hasher.update(&sibling);
+            hasher.update(&acc);
+        } else {
+            hasher.update(&acc);
+            hasher.update(&sibling);
+        }
+        Some(hasher.finalize().to_vec())
+    });
+
+    match folded {
+        Some(root_candidate) => hex::encode(&root_candidate) == root,
+        None => false,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    
+    use crate::store::MemoryStore;
 
     #[test]
     fn test_uncle_root() {
@@ -188,6 +593,56 @@ mod tests {
         assert_eq!(tree.nodes.len(), 64);
     }
 
+    #[test]
+    fn test_tree_advertises_algorithm_and_chunk_size() {
+        let tree = get_icons_rgb_circle_tree();
+
+        assert_eq!(tree.algorithm, "sha256");
+        assert_eq!(tree.chunk_size, DEFAULT_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn test_blake2s256_tree_advertises_its_own_algorithm_and_root() {
+        let sha_tree = get_icons_rgb_circle_tree();
+        let blake_tree =
+            MerkleTree::<Blake2s256>::new("test_data/icons_rgb_circle.png", DEFAULT_CHUNK_SIZE);
+
+        assert_eq!(blake_tree.algorithm, "blake2s256");
+        assert_ne!(blake_tree.nodes[1], sha_tree.nodes[1]);
+    }
+
+    #[test]
+    fn test_algorithm_parse() {
+        assert_eq!(Algorithm::parse("sha256"), Some(Algorithm::Sha256));
+        assert_eq!(Algorithm::parse("blake2s256"), Some(Algorithm::Blake2s256));
+        assert_eq!(Algorithm::parse("keccak"), None);
+    }
+
+    #[test]
+    fn test_any_merkle_tree_dispatches_by_algorithm() {
+        let mut store = MemoryStore::new();
+        let sha_tree = AnyMerkleTree::open(
+            "test_data/icons_rgb_circle.png",
+            DEFAULT_CHUNK_SIZE,
+            Algorithm::Sha256,
+            &mut store,
+        );
+        let blake_tree = AnyMerkleTree::open(
+            "test_data/icons_rgb_circle.png",
+            DEFAULT_CHUNK_SIZE,
+            Algorithm::Blake2s256,
+            &mut store,
+        );
+
+        assert_eq!(sha_tree.algorithm(), "sha256");
+        assert_eq!(blake_tree.algorithm(), "blake2s256");
+        assert_ne!(sha_tree.root_hex(), blake_tree.root_hex());
+
+        let proof = sha_tree.audit_path(4).unwrap();
+        let piece = sha_tree.read_piece(4).unwrap();
+        assert!(sha_tree.verify(&sha_tree.root_hex(), 4, &piece, &proof));
+    }
+
     #[test]
     fn test_uncle_traversal() {
         let tree = get_icons_rgb_circle_tree();
@@ -221,17 +676,62 @@ mod tests {
     }
 
     #[test]
-    fn test_piece_data() {
+    fn test_audit_path() {
         let tree = get_icons_rgb_circle_tree();
+        let result = tree.audit_path(8);
 
-        assert_eq!(tree.piece_data.get(&8).unwrap(),"1wSDXYz+dPEXQP9oAYKE7Tz5ttGgCYkD3ile/OXpP4AAAPTqv+BlsRiHgknDtgQv/orRny7+AhAAgB7a+tKLxbYEp8bkJiY7bdm/L7n35ek/QN/NOQMAGYi+8c17X7AQLf8MUxOjP83+B+jzn71XLs+ZAgQZiKkxO7QCtffz27kjyYu/zP0HGAwtQJCJGA36zFtvWIgWSrWF646n/wACAFBzIfnqL7qTZGiXFC/+uvPpZ0Z/AvTfpW4AmL9yedpaQD5iKpDRoO0q/lMc/an9B2Ag5roBwDpAXuI8wLOnTlqItgSABEd/xsVf97/+xocLMCACAGQoxkkaDdqGz2m8e3YjNXc+1fsPMCDfLQ0As9YD8vLM735jNGjDpdj7Hxd/Gf0JMDAzSwOAUaCQoWdPn3QeoKHi4i+jPwGogxYgyPkPgOefK3YYDdpITyfYouXiL4CBe6AFyG3AkKl4ymw0aLPErkyK7T93P/+imL923QcMMDhagIAFMRo0Wk5ohij+Y1pTam5+OOXDBWgALUBAt9jcaTRoY6Q4oenu+S9d/AUweHNLA8C09YC8xbjJ7a93LMSARTuWi78AqMP8lcsPBACAYvuvj3VnzzM42xK8+CtGf9676KgZQFMsBoA5SwGEnSffNhp0QOJehrikLTV6/wEa4cIDAWBxOwAg2k/iUDD9t83oTwD68b/1S/71VcsBhK0vvZjkGMomi10XF38BUKO5lQKABk3gB8+89Ua3JYX+SPHpf7jj6T9AowMAwA9iNOgOrUACwEaK/08/M/oToDm+WykATFsXYKkYDRo7AdQr1Yu/tP8ANMrMSgEA4CHbXv2F0aB1B4AER3/euzhb3P/6Gx8uQHOsuAPgDACwomdPnzQatCYRrmKnJTV3PtX7D9Ak81cur7gD8J2lAVYS7SnPnjppIWqQYu9/XPxl9CdAc9kBAFYlLqhKdVLNoDz10590R66mRu8/QONcWDEAzF+5bAcAeKxnfvcbo0F76GkXfwEwAMsPAc9aEuBxYjSo8wAbF2uY4mVrdz//opi/dt0HDNAs00v/j83L/p92AViXexdniv+z76VsC7mRf/mnYuj557J4v3FgdcdbbxTX3nnPF38DUh39efPDKR8uQPM8UOMPPS4dAE8WTzu/f/NEVu95+PDLxdZDB3z4G5DieYq757908RdAM808LgDYAYB1iHnnNz86k9V73vn7t7uHWFm7CE8p7hg5/AvQWHOPCwAmAcE6RetDXH6Ui2hfifMArN22V9Mc/RmtgAA0z/yVy48NAHOWCNbv+jvvdaeg5CJGg25/veODX4OYohTrlmIABqCRHno6OfS4dACsTfQ/53Y4dvuvj3Vvs2V1thn9CQ==");
+        assert_eq!(
+            result.unwrap(),
+            vec![
+                ("6a10a0b8c1bd3651cba6e5604b31df595e965be137650d296c05afc1084cfe1f".to_string(), false),
+                ("956bf86d100b2f49a8d057ebafa85b8db89a0f19d5627a1226fea1cb3e23d3f3".to_string(), false),
+                ("04284ddea22b003e6098e7dd1a421a565380d11530a35f2e711a8dd2b9b5e7f8".to_string(), false),
+                ("c66a821b749e0576e54b89dbac8f71211a508f7916e3d6235900372bed6c6c22".to_string(), true),
+                ("a8bd48117723dee92524c25730f9e08e5d47e78c87d17edb344d4070389d049e".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_round_trips_through_audit_path() {
+        let tree = get_icons_rgb_circle_tree();
+        let root = hex::encode(&tree.nodes[1]);
+        let proof = tree.audit_path(8).unwrap();
+        let piece_bytes = tree.read_piece(8).unwrap();
+
+        assert!(verify::<Sha256>(&root, 8, &piece_bytes, tree.chunk_size, &proof));
     }
 
     #[test]
-    fn test_piece_data_out_of_bounds() {
+    fn test_verify_rejects_tampered_piece() {
         let tree = get_icons_rgb_circle_tree();
+        let root = hex::encode(&tree.nodes[1]);
+        let proof = tree.audit_path(8).unwrap();
+        let mut piece_bytes = tree.read_piece(8).unwrap();
+        piece_bytes[0] ^= 0xff;
 
-        assert_eq!(tree.piece_data.get(&17), None);
+        assert!(!verify::<Sha256>(&root, 8, &piece_bytes, tree.chunk_size, &proof));
+    }
+
+    #[test]
+    fn test_read_piece() {
+        let tree = get_icons_rgb_circle_tree();
+
+        assert_eq!(base64::encode(tree.read_piece(8).unwrap()),"1wSDXYz+dPEXQP9oAYKE7Tz5ttGgCYkD3ile/OXpP4AAAPTqv+BlsRiHgknDtgQv/orRny7+AhAAgB7a+tKLxbYEp8bkJiY7bdm/L7n35ek/QN/NOQMAGYi+8c17X7AQLf8MUxOjP83+B+jzn71XLs+ZAgQZiKkxO7QCtffz27kjyYu/zP0HGAwtQJCJGA36zFtvWIgWSrWF646n/wACAFBzIfnqL7qTZGiXFC/+uvPpZ0Z/AvTfpW4AmL9yedpaQD5iKpDRoO0q/lMc/an9B2Ag5roBwDpAXuI8wLOnTlqItgSABEd/xsVf97/+xocLMCACAGQoxkkaDdqGz2m8e3YjNXc+1fsPMCDfLQ0As9YD8vLM735jNGjDpdj7Hxd/Gf0JMDAzSwOAUaCQoWdPn3QeoKHi4i+jPwGogxYgyPkPgOefK3YYDdpITyfYouXiL4CBe6AFyG3AkKl4ymw0aLPErkyK7T93P/+imL923QcMMDhagIAFMRo0Wk5ohij+Y1pTam5+OOXDBWgALUBAt9jcaTRoY6Q4oenu+S9d/AUweHNLA8C09YC8xbjJ7a93LMSARTuWi78AqMP8lcsPBACAYvuvj3VnzzM42xK8+CtGf9676KgZQFMsBoA5SwGEnSffNhp0QOJehrikLTV6/wEa4cIDAWBxOwAg2k/iUDD9t83oTwD68b/1S/71VcsBhK0vvZjkGMomi10XF38BUKO5lQKABk3gB8+89Ua3JYX+SPHpf7jj6T9AowMAwA9iNOgOrUACwEaK/08/M/oToDm+WykATFsXYKkYDRo7AdQr1Yu/tP8ANMrMSgEA4CHbXv2F0aB1B4AER3/euzhb3P/6Gx8uQHOsuAPgDACwomdPnzQatCYRrmKnJTV3PtX7D9Ak81cur7gD8J2lAVYS7SnPnjppIWqQYu9/XPxl9CdAc9kBAFYlLqhKdVLNoDz10590R66mRu8/QONcWDEAzF+5bAcAeKxnfvcbo0F76GkXfwEwAMsPAc9aEuBxYjSo8wAbF2uY4mVrdz//opi/dt0HDNAs00v/j83L/p92AViXexdniv+z76VsC7mRf/mnYuj557J4v3FgdcdbbxTX3nnPF38DUh39efPDKR8uQPM8UOMPPS4dAE8WTzu/f/NEVu95+PDLxdZDB3z4G5DieYq757908RdAM808LgDYAYB1iHnnNz86k9V73vn7t7uHWFm7CE8p7hg5/AvQWHOPCwAmAcE6RetDXH6Ui2hfifMArN22V9Mc/RmtgAA0z/yVy48NAHOWCNbv+jvvdaeg5CJGg25/veODX4OYohTrlmIABqCRHno6OfS4dACsTfQ/53Y4dvuvj3Vvs2V1thn9CQ==");
+    }
+
+    #[test]
+    fn test_read_piece_out_of_bounds() {
+        let tree = get_icons_rgb_circle_tree();
+
+        assert_eq!(tree.read_piece(17), None);
+    }
+
+    #[test]
+    fn test_audit_path_out_of_bounds_on_power_of_two_leaf_layer() {
+        let tree = get_single_element_tree();
+
+        assert_eq!(tree.audit_path(1), None);
     }
 
     #[test]
@@ -286,15 +786,85 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_open_rebuilds_on_first_run() {
+        let mut store = MemoryStore::new();
+        let tree = MerkleTree::<Sha256>::open_default("test_data/icons_rgb_circle.png", &mut store);
+
+        assert_eq!(
+            hex::encode(&tree.nodes[1]),
+            "9b39e1edb4858f7a3424d5a3d0c4579332640e58e101c29f99314a12329fc60b"
+        );
+    }
+
+    #[test]
+    fn test_open_skips_rehash_on_second_run() {
+        let mut store = MemoryStore::new();
+        let first = MerkleTree::<Sha256>::open_default("test_data/icons_rgb_circle.png", &mut store);
+        let second = MerkleTree::<Sha256>::open_default("test_data/icons_rgb_circle.png", &mut store);
+
+        assert_eq!(first.nodes[1], second.nodes[1]);
+        assert_eq!(first.piece_locations, second.piece_locations);
+    }
+
+    #[test]
+    fn test_open_rebuilds_when_file_content_changes() {
+        let dir = std::env::temp_dir().join("merkle_file_server_stale_cache_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("mutable.bin");
+
+        std::fs::write(&path, vec![b'A'; DEFAULT_CHUNK_SIZE]).unwrap();
+        let mut store = MemoryStore::new();
+        let first = MerkleTree::<Sha256>::open_default(&path, &mut store);
+
+        std::fs::write(&path, vec![b'B'; 17]).unwrap();
+        let second = MerkleTree::<Sha256>::open_default(&path, &mut store);
+
+        assert_ne!(first.nodes[1], second.nodes[1]);
+        assert_eq!(second.read_piece(0).unwrap()[..17], vec![b'B'; 17][..]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_persist_dedupes_shared_leaf_across_trees() {
+        let dir = std::env::temp_dir().join("merkle_file_server_dedupe_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file_a = dir.join("a.bin");
+        let file_b = dir.join("b.bin");
+        std::fs::write(&file_a, vec![b'A'; DEFAULT_CHUNK_SIZE]).unwrap();
+        std::fs::write(
+            &file_b,
+            [vec![b'A'; DEFAULT_CHUNK_SIZE], vec![b'B'; DEFAULT_CHUNK_SIZE]].concat(),
+        )
+        .unwrap();
+
+        let mut store = MemoryStore::new();
+        let tree_a = MerkleTree::<Sha256>::open_default(&file_a, &mut store);
+        let entries_after_a = store.len();
+
+        let tree_b = MerkleTree::<Sha256>::open_default(&file_b, &mut store);
+        let entries_after_b = store.len();
+
+        // tree_b's leading "A" chunk hashes the same as tree_a's only leaf,
+        // so it was already present under node_key(leaf) before tree_b was
+        // persisted: only the new root, the new "B" leaf, and tree_b's own
+        // tree/path entries should add new keys, not one per node.
+        assert_eq!(tree_b.nodes[2], tree_a.nodes[1]);
+        assert_eq!(entries_after_b - entries_after_a, 4);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
     fn get_icons_rgb_circle_tree() -> MerkleTree {
-        MerkleTree::new("test_data/icons_rgb_circle.png")
+        MerkleTree::new_default("test_data/icons_rgb_circle.png")
     }
 
     fn get_empty_tree() -> MerkleTree {
-        MerkleTree::new("test_data/test.txt")
+        MerkleTree::new_default("test_data/test.txt")
     }
 
     fn get_single_element_tree() -> MerkleTree {
-        MerkleTree::new("test_data/small.txt")
+        MerkleTree::new_default("test_data/small.txt")
     }
 }
@@ -0,0 +1,234 @@
+use crate::file_utils::DEFAULT_CHUNK_SIZE;
+use crate::merkle_tree::{hash_leaf, heap_audit_path, populate_tree, HashAlgorithm, Node};
+use crate::pad_vec;
+use sha2::Sha256;
+use std::marker::PhantomData;
+
+struct Peak<H: HashAlgorithm> {
+    height: usize,
+    leaves: Vec<Node>,
+    nodes: Vec<Node>,
+    _digest: PhantomData<H>,
+}
+
+impl<H: HashAlgorithm> Peak<H> {
+    fn leaf(leaf_hash: Node) -> Self {
+        Peak {
+            height: 0,
+            leaves: vec![leaf_hash.clone()],
+            nodes: vec![Node::new(), leaf_hash],
+            _digest: PhantomData,
+        }
+    }
+
+    fn merge(left: Peak<H>, right: Peak<H>) -> Self {
+        let mut leaves = left.leaves;
+        leaves.extend(right.leaves);
+
+        let leaf_layer_length = leaves.len();
+        let total_nodes = 2 * leaf_layer_length - 1;
+        let mut nodes = vec![Node::new(); total_nodes + 1];
+        for (i, leaf) in leaves.iter().cloned().enumerate() {
+            nodes[i + leaf_layer_length] = leaf;
+        }
+        populate_tree::<H>(&mut nodes, &leaf_layer_length);
+
+        Peak {
+            height: left.height + 1,
+            leaves,
+            nodes,
+            _digest: PhantomData,
+        }
+    }
+
+    fn root(&self) -> &Node {
+        &self.nodes[1]
+    }
+}
+
+pub struct MmrProof {
+    pub peak_index: usize,
+    pub peak_audit_path: Vec<(String, bool)>,
+    pub other_peaks: Vec<String>,
+}
+
+pub struct MerkleMountainRange<H: HashAlgorithm = Sha256> {
+    peaks: Vec<Peak<H>>,
+    pub(crate) total_leaves: usize,
+    chunk_size: usize,
+    _digest: PhantomData<H>,
+}
+
+impl<H: HashAlgorithm> MerkleMountainRange<H> {
+    pub fn new(chunk_size: usize) -> Self {
+        MerkleMountainRange {
+            peaks: Vec::new(),
+            total_leaves: 0,
+            chunk_size,
+            _digest: PhantomData,
+        }
+    }
+
+    pub fn append(&mut self, chunk: &[u8]) {
+        let mut peak = Peak::leaf(hash_leaf::<H>(&pad_vec(chunk, self.chunk_size)));
+
+        while self.peaks.last().map_or(false, |top| top.height == peak.height) {
+            let left = self.peaks.pop().unwrap();
+            peak = Peak::merge(left, peak);
+        }
+
+        self.peaks.push(peak);
+        self.total_leaves += 1;
+    }
+
+    pub fn root(&self) -> Option<String> {
+        let mut peaks = self.peaks.iter().rev();
+        let mut acc = peaks.next()?.root().clone();
+
+        for peak in peaks {
+            let mut hasher = H::new();
+            hasher.update(peak.root());
+            hasher.update(&acc);
+            acc = hasher.finalize().to_vec();
+        }
+
+        Some(hex::encode(acc))
+    }
+
+    pub fn proof(&self, leaf_number: usize) -> Option<MmrProof> {
+        let (peak_index, local_leaf_number) = self.locate(leaf_number)?;
+        let peak_audit_path = heap_audit_path(&self.peaks[peak_index].nodes, local_leaf_number);
+        let other_peaks = self
+            .peaks
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != peak_index)
+            .map(|(_, peak)| hex::encode(peak.root()))
+            .collect();
+
+        Some(MmrProof {
+            peak_index,
+            peak_audit_path,
+            other_peaks,
+        })
+    }
+
+    fn locate(&self, leaf_number: usize) -> Option<(usize, usize)> {
+        let mut start = 0;
+        for (index, peak) in self.peaks.iter().enumerate() {
+            if leaf_number < start + peak.leaves.len() {
+                return Some((index, leaf_number - start));
+            }
+            start += peak.leaves.len();
+        }
+        None
+    }
+}
+
+impl MerkleMountainRange<Sha256> {
+    pub fn new_default() -> Self {
+        Self::new(DEFAULT_CHUNK_SIZE)
+    }
+}
+
+pub fn verify<H: HashAlgorithm>(root: &str, leaf_bytes: &[u8], chunk_size: usize, proof: &MmrProof) -> bool {
+    let leaf = hash_leaf::<H>(&pad_vec(leaf_bytes, chunk_size));
+    let folded = proof.peak_audit_path.iter().try_fold(leaf, |acc, (sibling_hex, sibling_is_left)| {
+        let sibling = hex::decode(sibling_hex).ok()?;
+        let mut hasher = H::new();
+        if *sibling_is_left {
+            hasher.update(&sibling);
+            hasher.update(&acc);
+        } else {
+            hasher.update(&acc);
+            hasher.update(&sibling);
+        }
+        Some(hasher.finalize().to_vec())
+    });
+
+    let peak_root = match folded {
+        Some(candidate) => hex::encode(candidate),
+        None => return false,
+    };
+
+    let mut peaks = proof.other_peaks.clone();
+    if proof.peak_index > peaks.len() {
+        return false;
+    }
+    peaks.insert(proof.peak_index, peak_root);
+
+    let mut iter = peaks.iter().rev();
+    let mut acc = match iter.next().and_then(|hex_hash| hex::decode(hex_hash).ok()) {
+        Some(bytes) => bytes,
+        None => return false,
+    };
+
+    for sibling_hex in iter {
+        let sibling = match hex::decode(sibling_hex) {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+        let mut hasher = H::new();
+        hasher.update(&sibling);
+        hasher.update(&acc);
+        acc = hasher.finalize().to_vec();
+    }
+
+    hex::encode(acc) == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root_grows_with_appends() {
+        let mut mmr = MerkleMountainRange::<Sha256>::new_default();
+        assert_eq!(mmr.root(), None);
+
+        mmr.append(b"a");
+        let root_one = mmr.root().unwrap();
+
+        mmr.append(b"b");
+        let root_two = mmr.root().unwrap();
+
+        assert_ne!(root_one, root_two);
+    }
+
+    #[test]
+    fn test_peaks_bag_on_power_of_two_appends() {
+        let mut mmr = MerkleMountainRange::<Sha256>::new_default();
+        for chunk in [b"a", b"b", b"c", b"d"] {
+            mmr.append(chunk);
+        }
+
+        assert_eq!(mmr.peaks.len(), 1);
+        assert_eq!(mmr.total_leaves, 4);
+    }
+
+    #[test]
+    fn test_verify_round_trips_through_proof() {
+        let mut mmr = MerkleMountainRange::<Sha256>::new_default();
+        for chunk in [b"a", b"b", b"c"] {
+            mmr.append(chunk);
+        }
+
+        let root = mmr.root().unwrap();
+        let proof = mmr.proof(1).unwrap();
+
+        assert!(verify::<Sha256>(&root, b"b", mmr.chunk_size, &proof));
+    }
+
+    #[test]
+    fn test_verify_rejects_tampered_leaf() {
+        let mut mmr = MerkleMountainRange::<Sha256>::new_default();
+        for chunk in [b"a", b"b", b"c"] {
+            mmr.append(chunk);
+        }
+
+        let root = mmr.root().unwrap();
+        let proof = mmr.proof(1).unwrap();
+
+        assert!(!verify::<Sha256>(&root, b"z", mmr.chunk_size, &proof));
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A pluggable key-value backend for persisting Merkle nodes and piece
+/// bytes, keyed by their own content hash. Any backend that can durably
+/// store and retrieve a blob by key (an embedded KV store, a plain
+/// directory of files, ...) can implement this.
+pub trait NodeStore: std::fmt::Debug {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+    fn put(&mut self, key: &[u8], value: Vec<u8>);
+}
+
+/// Non-persistent backend, mainly useful for tests and one-off runs.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStore {
+    entries: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl NodeStore for MemoryStore {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.entries.get(key).cloned()
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) {
+        self.entries.insert(key.to_vec(), value);
+    }
+}
+
+#[cfg(test)]
+impl MemoryStore {
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// Persists each entry as its own file, named after the hex-encoded key,
+/// under `root`. This is enough to survive a server restart without
+/// pulling in a full embedded database.
+#[derive(Debug)]
+pub struct FsStore {
+    root: PathBuf,
+}
+
+impl FsStore {
+    pub fn open(root: impl AsRef<Path>) -> std::io::Result<Self> {
+        fs::create_dir_all(&root)?;
+        Ok(FsStore {
+            root: root.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path_for(&self, key: &[u8]) -> PathBuf {
+        self.root.join(hex::encode(key))
+    }
+}
+
+impl NodeStore for FsStore {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        fs::read(self.path_for(key)).ok()
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) {
+        let _ = fs::write(self.path_for(key), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_store_roundtrip() {
+        let mut store = MemoryStore::new();
+        assert_eq!(store.get(b"missing"), None);
+
+        store.put(b"key", b"value".to_vec());
+        assert_eq!(store.get(b"key"), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn test_fs_store_roundtrip() {
+        let dir = std::env::temp_dir().join("merkle_file_server_fs_store_test");
+        let mut store = FsStore::open(&dir).unwrap();
+
+        store.put(b"key", b"value".to_vec());
+        assert_eq!(store.get(b"key"), Some(b"value".to_vec()));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}